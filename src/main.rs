@@ -2,6 +2,11 @@ extern crate clap;
 extern crate kankyo;
 extern crate yansi;
 extern crate r2d2;
+extern crate sha2;
+extern crate openssl;
+extern crate toml;
+#[macro_use] extern crate serde_derive;
+extern crate chrono;
 #[macro_use] extern crate cdrs;
 
 use clap::*;
@@ -11,10 +16,13 @@ use std::env::var;
 use std::path::Path;
 
 use yansi::{Paint};
+use sha2::{Sha256, Digest};
+use openssl::ssl::{SslContext, SslMethod, SslVerifyMode};
+use chrono::Utc;
 
 use cdrs::authenticators::*;
-use cdrs::cluster::{ClusterTcpConfig, TcpConnectionsManager, NodeTcpConfigBuilder, session::{self, Session}};
-use cdrs::load_balancing::SingleNode;
+use cdrs::cluster::{ClusterTcpConfig, ClusterSslConfig, TcpConnectionsManager, SslConnectionsManager, NodeTcpConfigBuilder, NodeSslConfigBuilder, session::{self, Session}};
+use cdrs::load_balancing::{LoadBalancingStrategy, SingleNode, RoundRobin, Random};
 use cdrs::query::*;
 use cdrs::types::IntoRustByIndex;
 
@@ -78,38 +86,343 @@ impl<T> FancyResult<T> for Option<T> {
     }
 }
 
+/// Which `cdrs` load-balancing strategy to spread queries across the
+/// configured nodes with. Selected via `CASSANDRA_LB`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LbStrategy {
+    SingleNode,
+    RoundRobin,
+    Random
+}
+
+/// Position of `name` within `migrations`, in application order.
+fn migration_index(migrations: &[String], name: &str) -> usize {
+    migrations.iter().position(|x| x == name).ioexpect(&format!("Unknown migration: {}", name))
+}
+
+/// Timestamp-prefixed migration directories look like
+/// `20240115093000_add_users`: 14 digits, an underscore, then the name.
+fn is_timestamped(name: &str) -> bool {
+    name.len() > 15 && name.as_bytes()[14] == b'_' && name[..14].bytes().all(|b| b.is_ascii_digit())
+}
+
+/// The human-readable part of a migration identifier, stripping the
+/// timestamp prefix if present.
+fn migration_label(full: &str) -> &str {
+    if is_timestamped(full) { &full[15..] } else { full }
+}
+
+/// Lists timestamp-prefixed migration directories under `migrations_dir`,
+/// sorted lexicographically (which is chronological, since the prefix is a
+/// fixed-width UTC timestamp). Returns an empty vec if none exist.
+fn discover_timestamped_migrations(migrations_dir: &str) -> Vec<String> {
+    let mut dirs: Vec<String> = match fs::read_dir(migrations_dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().is_dir())
+            .filter_map(|e| e.file_name().into_string().ok())
+            .filter(|name| is_timestamped(name))
+            .collect(),
+        Err(_) => Vec::new()
+    };
+
+    dirs.sort();
+    dirs
+}
+
+/// Parses the legacy `vagabond` ordering file into a list of migration
+/// names, in order. Blank lines and `//`-comments are skipped.
+fn parse_vagabond_file(contents: &str) -> Vec<String> {
+    let mut migrations: Vec<String> = Vec::new();
+
+    for line in contents.split('\n') {
+        if !line.starts_with("//") && !line.is_empty() {
+            let line = line.to_owned();
+            if migrations.contains(&line) {
+                panic!("Migration name {} is already used!", &line);
+            }
+
+            migrations.push(line);
+        }
+    }
+
+    migrations
+}
+
+/// Merges the legacy, vagabond-file-ordered migrations with any timestamped
+/// directories discovered on disk, appending timestamped entries that aren't
+/// already present (in their sorted/chronological order). This keeps
+/// pre-existing legacy migrations resolvable - including whichever one is
+/// currently recorded as applied in Cassandra - even after a repo starts
+/// adding timestamped migrations alongside them, instead of switching over
+/// and dropping the legacy ones the moment the first timestamped dir exists.
+fn merge_migrations(legacy: Vec<String>, timestamped: Vec<String>) -> Vec<String> {
+    let mut migrations = legacy;
+
+    for name in timestamped {
+        if !migrations.contains(&name) {
+            migrations.push(name);
+        }
+    }
+
+    migrations
+}
+
+#[cfg(test)]
+mod migration_list_tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    /// A scratch directory under the OS temp dir, removed on drop.
+    struct TempDir(std::path::PathBuf);
+
+    impl TempDir {
+        fn new() -> TempDir {
+            let n = COUNTER.fetch_add(1, Ordering::SeqCst);
+            let path = std::env::temp_dir().join(format!("vagabond-test-{}-{}", std::process::id(), n));
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+
+        fn mkdir(&self, name: &str) {
+            fs::create_dir(self.0.join(name)).unwrap();
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn discover_finds_only_timestamped_dirs_sorted() {
+        let dir = TempDir::new();
+        dir.mkdir("20240201000000_b");
+        dir.mkdir("20240101000000_a");
+        dir.mkdir("migration_legacy");
+        dir.mkdir("not_a_migration_dir.txt");
+
+        assert_eq!(
+            discover_timestamped_migrations(dir.path()),
+            vec!["20240101000000_a".to_owned(), "20240201000000_b".to_owned()]
+        );
+    }
+
+    #[test]
+    fn discover_returns_empty_for_only_legacy_dirs() {
+        let dir = TempDir::new();
+        dir.mkdir("migration_a");
+        dir.mkdir("migration_b");
+
+        assert!(discover_timestamped_migrations(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn merge_keeps_legacy_only_migrations_when_no_timestamped_dirs_exist() {
+        let legacy = vec!["migration_a".to_owned(), "migration_b".to_owned()];
+
+        assert_eq!(merge_migrations(legacy.clone(), Vec::new()), legacy);
+    }
+
+    #[test]
+    fn merge_returns_only_timestamped_when_no_legacy_migrations_exist() {
+        let timestamped = vec!["20240101000000_a".to_owned(), "20240201000000_b".to_owned()];
+
+        assert_eq!(merge_migrations(Vec::new(), timestamped.clone()), timestamped);
+    }
+
+    #[test]
+    fn merge_appends_new_timestamped_dirs_after_existing_legacy_migrations() {
+        let legacy = vec!["migration_a".to_owned(), "migration_b".to_owned()];
+        let timestamped = vec!["20240101000000_add_users".to_owned()];
+
+        assert_eq!(
+            merge_migrations(legacy, timestamped),
+            vec!["migration_a".to_owned(), "migration_b".to_owned(), "20240101000000_add_users".to_owned()]
+        );
+    }
+
+    #[test]
+    fn merge_does_not_duplicate_a_timestamped_migration_already_in_the_legacy_file() {
+        // Happens during the transition: `new` was still writing to the
+        // vagabond file when the timestamped dir was created, so the name
+        // ends up in both places.
+        let legacy = vec!["migration_a".to_owned(), "20240101000000_add_users".to_owned()];
+        let timestamped = vec!["20240101000000_add_users".to_owned()];
+
+        assert_eq!(
+            merge_migrations(legacy.clone(), timestamped),
+            legacy
+        );
+    }
+}
+
+impl LbStrategy {
+    fn parse(s: &str) -> Option<LbStrategy> {
+        match s.to_lowercase().as_str() {
+            "single" | "singlenode" => Some(LbStrategy::SingleNode),
+            "roundrobin" | "round-robin" | "round_robin" => Some(LbStrategy::RoundRobin),
+            "random" => Some(LbStrategy::Random),
+            _ => None
+        }
+    }
+}
+
+/// Default location of the optional config file, next to `migrations/`.
+const DEFAULT_CONFIG_PATH: &str = "./vagabond.toml";
+const DEFAULT_MIGRATIONS_DIR: &str = "./migrations";
+
+/// Scaffolded by `vagabond init`. Every setting here can also be set via
+/// the matching `CASSANDRA_*` environment variable, which takes priority.
+const EXAMPLE_CONFIG: &str = r#"# vagabond config file.
+# Every setting below can be overridden by its CASSANDRA_* environment
+# variable; env vars always win when both are set.
+
+# host = "127.0.0.1"
+# hosts = "10.0.0.1,10.0.0.2,10.0.0.3"
+# keyspace = "my_keyspace"
+# username = "cassandra"
+# password = "cassandra"
+# migrations_dir = "./migrations"
+
+# lb = "single"  # one of: single, roundrobin, random
+
+# tls = false
+# tls_ca = "/path/to/ca.pem"
+# tls_require = false  # refuse to start unless TLS ends up enabled
+"#;
+
+/// Shape of `vagabond.toml`. Every field is optional: anything missing
+/// falls back to its `CASSANDRA_*` environment variable, and env vars
+/// always win over the file when both are set.
+#[derive(Deserialize, Default)]
+struct FileConfig {
+    host: Option<String>,
+    hosts: Option<String>,
+    keyspace: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    migrations_dir: Option<String>,
+    lb: Option<String>,
+    tls: Option<bool>,
+    tls_ca: Option<String>,
+    tls_require: Option<bool>
+}
+
+fn load_file_config(path: &Path) -> FileConfig {
+    match fs::read_to_string(path) {
+        Ok(s) => toml::from_str(&s).ioexpect(&format!("Error parsing {}", path.display())),
+        Err(_) => FileConfig::default()
+    }
+}
+
+fn truthy(v: &str) -> bool {
+    v == "1" || v.eq_ignore_ascii_case("true")
+}
+
 pub struct Config {
     vagabond: String,
 
     migrations: Vec<String>,
-    host: String,
+    migrations_dir: String,
+    uses_vagabond_file: bool,
+    hosts: Vec<String>,
+    lb: LbStrategy,
     username: Option<String>,
     password: Option<String>,
-    keyspace: Option<String>
+    keyspace: Option<String>,
+
+    tls: bool,
+    tls_ca_cert: Option<String>,
+    tls_require: bool
 }
 
-fn get_cfg() -> Config {
-    let mut migrations: Vec<String> = Vec::new();
+/// Resolves the migrations directory the same way `get_cfg` does, without
+/// requiring the rest of the config. Used by `init`, which runs before a
+/// `vagabond` file or any migrations exist to build the rest of `Config` from.
+fn resolve_migrations_dir(file: &FileConfig) -> String {
+    var("CASSANDRA_MIGRATIONS_DIR").ok()
+        .or_else(|| file.migrations_dir.clone())
+        .unwrap_or_else(|| DEFAULT_MIGRATIONS_DIR.to_owned())
+}
+
+fn get_cfg(config_path: Option<&str>) -> Config {
+    let file = load_file_config(Path::new(config_path.unwrap_or(DEFAULT_CONFIG_PATH)));
+
+    let migrations_dir = resolve_migrations_dir(&file);
 
-    let s = fs::read_to_string("./migrations/vagabond").ioexpect("Error reading vagabond. Make sure the directory is intialized.");
-    for line in s.split('\n') {
-        if !line.starts_with("//") {
-            let lines = line.to_owned();
-            if migrations.contains(&lines) {
-                panic!("Migration name {} is already used!", &lines);
+    // Timestamped migration directories are merged on top of the legacy
+    // `vagabond` ordering file rather than replacing it outright, so a repo
+    // that adds its first timestamped migration doesn't lose track of
+    // whichever legacy migration is still recorded as applied in Cassandra.
+    // `init` no longer creates the `vagabond` file at all - fresh repos are
+    // pure-timestamp - so its absence only means "uninitialized" when
+    // `migrations_dir` itself doesn't exist either.
+    let timestamped = discover_timestamped_migrations(&migrations_dir);
+
+    let (legacy, vagabond, uses_vagabond_file) = match fs::read_to_string(format!("{}/vagabond", migrations_dir)) {
+        Ok(s) => {
+            let legacy = parse_vagabond_file(&s);
+            (legacy, s, true)
+        },
+        Err(_) => {
+            if !Path::new(&migrations_dir).is_dir() {
+                iored("Error reading vagabond. Make sure the directory is intialized.");
+                std::process::exit(-1);
             }
 
-            migrations.push(lines);
+            (Vec::new(), String::new(), false)
         }
+    };
+
+    let migrations = merge_migrations(legacy, timestamped);
+
+    let hosts_var = var("CASSANDRA_HOSTS").ok()
+        .or_else(|| var("CASSANDRA_HOST").ok())
+        .or_else(|| file.hosts.clone())
+        .or_else(|| file.host.clone())
+        .ioexpect("No hosts configured. Set CASSANDRA_HOSTS (or CASSANDRA_HOST), or `hosts`/`host` in vagabond.toml");
+    let hosts: Vec<String> = hosts_var.split(',').map(|h| h.trim().to_owned()).filter(|h| !h.is_empty()).collect();
+
+    let lb = match var("CASSANDRA_LB").ok().or_else(|| file.lb.clone()) {
+        Some(s) => LbStrategy::parse(&s).ioexpect("Invalid CASSANDRA_LB/lb value. Expected one of: single, roundrobin, random"),
+        None => LbStrategy::SingleNode
+    };
+
+    let tls_ca_cert = var("CASSANDRA_TLS_CA").ok().or_else(|| file.tls_ca.clone());
+    let tls = tls_ca_cert.is_some() ||
+        var("CASSANDRA_TLS").ok().map(|v| truthy(&v)).unwrap_or_else(|| file.tls.unwrap_or(false));
+
+    // tls_require no longer gates certificate verification (build_ssl_context
+    // always verifies once TLS is in play) - it means "refuse to run without
+    // TLS" instead, so a misconfigured deployment fails loudly instead of
+    // silently falling back to plaintext.
+    let tls_require = var("CASSANDRA_TLS_REQUIRE").ok().map(|v| truthy(&v)).unwrap_or_else(|| file.tls_require.unwrap_or(false));
+    if tls_require && !tls {
+        iored("CASSANDRA_TLS_REQUIRE/tls_require is set, but TLS isn't configured (set CASSANDRA_TLS=true or CASSANDRA_TLS_CA). Refusing to connect in plaintext.");
+        std::process::exit(-1);
     }
 
     Config {
-        vagabond: s,
+        vagabond,
         migrations,
-        host: var("CASSANDRA_HOST").ioexpect("Required CASSANDRA_HOST environment variable not found"),
-        username: var("CASSANDRA_USER").ok(),
-        password: var("CASSANDRA_PASSWORD").ok(),
-        keyspace: var("CASSANDRA_KEYSPACE").ok()
+        migrations_dir,
+        uses_vagabond_file,
+        hosts,
+        lb,
+        username: var("CASSANDRA_USER").ok().or_else(|| file.username.clone()),
+        password: var("CASSANDRA_PASSWORD").ok().or_else(|| file.password.clone()),
+        keyspace: var("CASSANDRA_KEYSPACE").ok().or_else(|| file.keyspace.clone()),
+        tls,
+        tls_ca_cert,
+        tls_require
     }
 }
 
@@ -135,23 +448,43 @@ impl Authenticator for PasswordOrNoneAuth {
     }
 }
 
-pub type VBSession<'a> = Session<SingleNode<r2d2::Pool<TcpConnectionsManager<PasswordOrNoneAuth>>>>;
+pub type VBSession<LB> = Session<LB>;
 
-fn init_single_connection(cfg: &Config) -> VBSession {
-    let auth = match (&cfg.username, &cfg.password) {
+fn build_auth(cfg: &Config) -> PasswordOrNoneAuth {
+    match (&cfg.username, &cfg.password) {
         (Some(user), Some(pass)) =>
             PasswordOrNoneAuth::Password(StaticPasswordAuthenticator::new(user.clone(), pass.clone())),
         (None, None) => PasswordOrNoneAuth::NoAuth(NoneAuthenticator),
         _ => {
             ioprint("One of username and password have been provided, but not both. Continuing with no authentication.");
-            
+
             PasswordOrNoneAuth::NoAuth(NoneAuthenticator {})
         }
-    };
+    }
+}
 
-    let ses = session::new(&ClusterTcpConfig(vec![NodeTcpConfigBuilder::new(&cfg.host, auth).build()]), SingleNode::new())
-        .ioexpect("Error initializing session");
+/// Builds the SSL context used for TLS connections, honoring the CA
+/// certificate from `cfg`. Always verifies the server certificate
+/// (`SslVerifyMode::PEER`) - TLS without verification is no protection
+/// against a man-in-the-middle, so there's no way to opt out of it.
+fn build_ssl_context(cfg: &Config) -> SslContext {
+    let mut builder = openssl::ssl::SslContext::builder(SslMethod::tls()).ioexpect("Error creating SSL context");
+
+    if let Some(ca) = &cfg.tls_ca_cert {
+        builder.set_ca_file(ca).ioexpect("Error loading CASSANDRA_TLS_CA certificate");
+    }
 
+    builder.set_verify(SslVerifyMode::PEER);
+
+    builder.build()
+}
+
+/// Sets the keyspace and ensures the `vagabond` table (and its checksum
+/// column) exists. Shared by both the TCP and TLS connectors.
+fn finish_session_setup<N, LB>(ses: &VBSession<LB>, cfg: &Config)
+where
+    LB: LoadBalancingStrategy<N>
+{
     if let Some(x) = &cfg.keyspace {
         //injection but meh, apparently you cant bind variables to USE
         ses.query(format!("USE {}", x)).ioexpect("Error setting keyspace. Does it exist?");
@@ -159,179 +492,375 @@ fn init_single_connection(cfg: &Config) -> VBSession {
         ioprint("No keyspace specified. The next operation may or may not error.")
     }
 
-    ses.query("CREATE TABLE IF NOT EXISTS vagabond (migration TEXT, PRIMARY KEY(migration));").ioexpect("Error creating vagabond table");
+    ses.query("CREATE TABLE IF NOT EXISTS vagabond (migration TEXT, checksum TEXT, PRIMARY KEY(migration));").ioexpect("Error creating vagabond table");
+
+    // Tables created before drift detection existed won't have this column.
+    // ALTER TABLE errors if it's already there, so we just ignore that case.
+    let _ = ses.query("ALTER TABLE vagabond ADD checksum TEXT;");
+}
+
+fn connect_tcp<LB>(cfg: &Config, lb: LB) -> VBSession<LB>
+where
+    LB: LoadBalancingStrategy<r2d2::Pool<TcpConnectionsManager<PasswordOrNoneAuth>>>
+{
+    let auth = build_auth(cfg);
+
+    let nodes = cfg.hosts.iter()
+        .map(|host| NodeTcpConfigBuilder::new(host, auth.clone()).build())
+        .collect();
+
+    let ses = session::new(&ClusterTcpConfig(nodes), lb)
+        .ioexpect("Error initializing session");
+
+    finish_session_setup(&ses, cfg);
     ses
 }
 
-fn apply_migration(session: &VBSession, migration: String) {
-    for query in migration.split(";") {
+fn connect_ssl<LB>(cfg: &Config, lb: LB) -> VBSession<LB>
+where
+    LB: LoadBalancingStrategy<r2d2::Pool<SslConnectionsManager<PasswordOrNoneAuth>>>
+{
+    let auth = build_auth(cfg);
+    let ssl = build_ssl_context(cfg);
+
+    let nodes = cfg.hosts.iter()
+        .map(|host| NodeSslConfigBuilder::new(host, &ssl, auth.clone()).build())
+        .collect();
+
+    let ses = session::new(&ClusterSslConfig(nodes), lb)
+        .ioexpect("Error initializing session");
+
+    finish_session_setup(&ses, cfg);
+    ses
+}
+
+/// Computes a SHA-256 over a migration's `up.cql` (and `down.cql`, if
+/// present) so we can detect someone editing an already-applied migration.
+fn migration_checksum(migration_dir: &Path) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(fs::read(migration_dir.join("up.cql")).ioexpect("Error reading up.cql"));
+
+    if let Ok(down) = fs::read(migration_dir.join("down.cql")) {
+        hasher.update(down);
+    }
+
+    format!("{:x}", hasher.finalize())
+}
+
+/// Warns (in red, via `iored`) if the on-disk migration no longer matches
+/// the checksum recorded when it was applied. A `None` checksum means the
+/// migration was applied before drift detection existed.
+fn warn_on_drift(name: &str, stored_checksum: &Option<String>, migrations_dir: &str) {
+    let actual = migration_checksum(&Path::new(migrations_dir).join(name));
+
+    match stored_checksum {
+        None => ioprint(&format!("{}: no checksum on record (applied before drift detection was added); unverified.", name)),
+        Some(stored) if stored != &actual => iored(&format!("{}: checksum mismatch! The applied schema no longer matches up.cql/down.cql on disk.", name)),
+        Some(_) => ()
+    }
+}
+
+/// Splits `sql` into its `;`-separated statements, stopping at the first
+/// empty chunk so a trailing `;` doesn't produce a spurious final statement.
+fn split_statements(sql: &str) -> Vec<&str> {
+    let mut statements = Vec::new();
+
+    for query in sql.split(";") {
         if query.len() == 0 {
             break;
         }
 
-        session.query(query).ioexpect(&format!("Error applying migration query: {}. You should probably clean this up", query));
+        statements.push(query);
+    }
+
+    statements
+}
+
+#[cfg(test)]
+mod split_statements_tests {
+    use super::*;
+
+    #[test]
+    fn splits_on_semicolons() {
+        assert_eq!(split_statements("A;B;C"), vec!["A", "B", "C"]);
+    }
+
+    #[test]
+    fn ignores_a_single_trailing_semicolon() {
+        assert_eq!(split_statements("A;B;"), vec!["A", "B"]);
+    }
+
+    #[test]
+    fn empty_sql_has_no_statements() {
+        assert!(split_statements("").is_empty());
+    }
+}
+
+/// Runs `statements` in order, stopping as soon as one fails and returning
+/// both its error and how many statements before it had already succeeded.
+fn run_statement_list<N, LB>(session: &VBSession<LB>, statements: &[&str]) -> Result<(), (usize, String)>
+where
+    LB: LoadBalancingStrategy<N>
+{
+    for (i, query) in statements.iter().enumerate() {
+        session.query(*query).map_err(|e| (i, format!("{}: {}", query, e)))?;
+    }
+
+    Ok(())
+}
+
+/// Runs each `;`-separated statement in `sql` in order, stopping and
+/// returning the failing statement's error as soon as one fails.
+fn run_statements<N, LB>(session: &VBSession<LB>, sql: &str) -> Result<(), String>
+where
+    LB: LoadBalancingStrategy<N>
+{
+    run_statement_list(session, &split_statements(sql)).map_err(|(_, e)| e)
+}
+
+fn apply_migration<N, LB>(session: &VBSession<LB>, migration: String)
+where
+    LB: LoadBalancingStrategy<N>
+{
+    run_statements(session, &migration).ioexpect("Error applying migration query. You should probably clean this up");
+}
+
+/// What happened when a migration failed partway through and we tried to
+/// auto-revert it via the paired `down.cql`.
+pub enum ApplyOutcome {
+    /// The failing `up.cql` was fully undone; the keyspace is as if the
+    /// migration was never attempted.
+    RevertedCleanly,
+    /// The revert itself failed (or `down.cql` couldn't be read). Cassandra
+    /// has no DDL transactions, so this is the genuinely-manual case.
+    RevertFailed(String)
+}
+
+/// Applies `up.cql` statement-by-statement. If one fails partway through,
+/// reverts via the paired `down.cql` instead of leaving the keyspace
+/// half-migrated, so `apply` stays safe to re-run.
+///
+/// `down.cql` is assumed to mirror `up.cql` 1:1 in reverse order, which is
+/// how migrations are conventionally written (e.g. `up.cql` does
+/// `CREATE TABLE A; CREATE TABLE B; CREATE INDEX …;`, `down.cql` does
+/// `DROP INDEX …; DROP TABLE B; DROP TABLE A;`). So if only the first N
+/// statements of `up.cql` ran before it failed, only the *last* N statements
+/// of `down.cql` - the ones that undo them - are replayed, instead of
+/// running all of `down.cql` and failing on steps that target things which
+/// were never created.
+fn apply_with_rollback<N, LB>(session: &VBSession<LB>, migration_dir: &Path) -> Result<(), ApplyOutcome>
+where
+    LB: LoadBalancingStrategy<N>
+{
+    let up = fs::read_to_string(migration_dir.join("up.cql")).ioexpect("Error reading up.cql");
+    let up_statements = split_statements(&up);
+
+    if let Err((succeeded, e)) = run_statement_list(session, &up_statements) {
+        iored(&format!("Error applying migration: {}", e));
+        ioprint("Attempting to revert via down.cql...");
+
+        let down = match fs::read_to_string(migration_dir.join("down.cql")) {
+            Ok(down) => down,
+            Err(e2) => return Err(ApplyOutcome::RevertFailed(format!("could not read down.cql: {}", e2)))
+        };
+
+        let down_statements = split_statements(&down);
+
+        if succeeded > down_statements.len() {
+            return Err(ApplyOutcome::RevertFailed(format!(
+                "up.cql ran {} statement(s) before failing, but down.cql only has {} - can't tell which ones undo them",
+                succeeded, down_statements.len()
+            )));
+        }
+
+        let to_revert = &down_statements[down_statements.len() - succeeded..];
+
+        return match run_statement_list(session, to_revert) {
+            Ok(_) => Err(ApplyOutcome::RevertedCleanly),
+            Err((_, e2)) => Err(ApplyOutcome::RevertFailed(e2))
+        };
     }
+
+    Ok(())
 }
 
-fn get_current_migration(session: &VBSession) -> Option<String> {
-    let body = session.query("SELECT migration FROM vagabond").unwrap().get_body().unwrap();
+fn get_current_migration<N, LB>(session: &VBSession<LB>) -> Option<String>
+where
+    LB: LoadBalancingStrategy<N>
+{
+    get_current_migration_record(session).map(|(name, _)| name)
+}
+
+/// Like `get_current_migration`, but also returns the checksum recorded
+/// alongside it (`None` if it was applied before drift detection existed).
+fn get_current_migration_record<N, LB>(session: &VBSession<LB>) -> Option<(String, Option<String>)>
+where
+    LB: LoadBalancingStrategy<N>
+{
+    let body = session.query("SELECT migration, checksum FROM vagabond").unwrap().get_body().unwrap();
     if let Some(rows) = body.into_rows() {
         return rows.first().map(|x| {
-            x.get_r_by_index(0).unwrap()
+            (x.get_r_by_index(0).unwrap(), x.get_by_index(1).unwrap())
         });
     }
 
     None
 }
 
-fn del_current_migration(session: &VBSession) {
+fn del_current_migration<N, LB>(session: &VBSession<LB>)
+where
+    LB: LoadBalancingStrategy<N>
+{
     session.query("TRUNCATE vagabond").unwrap();
 }
 
-fn set_current_migration(session: &VBSession, name: &str) {
+fn set_current_migration<N, LB>(session: &VBSession<LB>, name: &str, checksum: &str)
+where
+    LB: LoadBalancingStrategy<N>
+{
     del_current_migration(session);
-    session.query_with_values("INSERT INTO vagabond (migration) VALUES (?)", query_values!(name)).ioexpect("Error setting migration in database");
+    session.query_with_values("INSERT INTO vagabond (migration, checksum) VALUES (?, ?)", query_values!(name, checksum)).ioexpect("Error setting migration in database");
 }
 
-fn main() {
-    kankyo::load().unwrap();
+/// Runs every subcommand that needs a live session. `connect` builds that
+/// session, so the caller picks the load-balancing strategy and transport
+/// (TCP/TLS) once up front and everything below stays agnostic to both.
+fn run_with_session<N, LB>(cfg: Config, connect: impl FnOnce(&Config) -> VBSession<LB>, sub: &str, sub_matches: Option<&ArgMatches>)
+where
+    LB: LoadBalancingStrategy<N>
+{
+    let ses = connect(&cfg);
 
-    let matches = clap_app!(vagabond =>
-        (version: crate_version!())
-        (author: crate_authors!())
-        (about: crate_description!())
-        (about: "A very simple cassandra migration tool for rust.")
-        
-        (@subcommand init =>
-            (about: "Initialize the migrations directory")
-        )
+    match sub {
+        "redo" => {
+            let name = get_current_migration(&ses).ioexpect("No migration currently applied");
+            let path = Path::new(&cfg.migrations_dir).join(&name);
 
-        (@subcommand new =>
-            (about: "Add new migration")
-            (@arg NAME: * "Name of migration")
-        )
-
-        (@subcommand redo => 
-            (about: "Undoes and applies the last migration")
-        )
-
-        (@subcommand rollback => 
-            (about: "Undoes the last migration")
-        )
-
-        (@subcommand apply => 
-            (about: "Applies the next migration")
-        )
+            ioprint("Applying down.cql");
+            let down = fs::read_to_string(path.join("down.cql")).ioexpect("Error reading down.cql");
+            run_statements(&ses, &down).ioexpect("Error applying down.cql. You should probably clean this up");
 
-        (@subcommand delete => 
-            (about: "Deletes all unapplied migrations")
-        )
-    ).get_matches();
-    
-    match matches.subcommand() {
-        ("init", _) => {
-            fs::create_dir("./migrations").ioexpect("Cannot create directory");
-            fs::write("./migrations/vagabond", "//list of migration names in order, current migration is stored in db.").ioexpect("Error writing file");
-            iook("./migrations initialized");
+            ioprint("Applying up.cql");
+            match apply_with_rollback(&ses, &path) {
+                Ok(_) => {
+                    // Refresh the stored checksum: redo is how you bring an
+                    // edited, already-applied migration back in sync, so the
+                    // next `status`/`apply` shouldn't report false drift.
+                    let checksum = migration_checksum(&path);
+                    set_current_migration(&ses, &name, &checksum);
+
+                    iook("Redone successfully");
+                },
+                Err(ApplyOutcome::RevertedCleanly) => {
+                    iook(&format!("{} failed partway through and its up.cql was reverted cleanly, but down.cql had already run: the migration is no longer applied, though it is still recorded as the current one.", name));
+                    std::process::exit(-1);
+                },
+                Err(ApplyOutcome::RevertFailed(e)) => {
+                    iored(&format!("{} failed partway through AND the automatic revert failed: {}. The keyspace is in an indeterminate state and needs manual cleanup.", name, e));
+                    std::process::exit(-1);
+                }
+            }
         },
-        ("new", Some(args)) => {
-            let name: &str = args.value_of("NAME").unwrap();
-            let cfg = get_cfg();
-
-            if cfg.migrations.contains(&name.to_owned()) {
-                panic!("Migration name is already used!");
+        "rollback" => {
+            let to = sub_matches.and_then(|m| m.value_of("TO"));
+
+            // Validate --to before changing the database: an unknown name
+            // exits via migration_index's ioexpect, and a name ahead of the
+            // current migration can never be reached by rolling back.
+            if let Some(target) = to {
+                let target_idx = migration_index(&cfg.migrations, target);
+                let cur = get_current_migration(&ses).ioexpect("No migration currently applied");
+                let cur_idx = migration_index(&cfg.migrations, &cur);
+
+                if target_idx > cur_idx {
+                    iored(&format!("{} is ahead of the current migration ({}); nothing to roll back", target, cur));
+                    std::process::exit(-1);
+                }
             }
 
-            let mut path = Path::new("./migrations/").to_path_buf();
-            path.push(name);
+            loop {
+                let cur = get_current_migration(&ses).ioexpect("No migration currently applied");
 
-            fs::create_dir(&path).ioexpect("Cannot create directory");
-
-            path.push("./up.cql");
-            fs::write(&path, "").ioexpect("Error creating up.cql");
-            
-            fs::write(path.with_file_name("down.cql"), "").ioexpect("Error creating down.cql");
+                if to == Some(cur.as_str()) {
+                    break;
+                }
 
-            fs::write("./migrations/vagabond", format!("{}\n{}", cfg.vagabond, name)).ioexpect("Error writing to vagabond");
+                let dir = Path::new(&cfg.migrations_dir).join(&cur);
+                apply_migration(&ses, fs::read_to_string(dir.join("down.cql")).ioexpect("Error reading down.cql"));
 
-            iook("Migration created");
-        },
-        ("redo", _) => {
-            let cfg = get_cfg();
-            let ses = init_single_connection(&cfg);
+                let idx = migration_index(&cfg.migrations, &cur);
+                if idx > 0 {
+                    let prev = cfg.migrations[idx - 1].as_str();
+                    let checksum = migration_checksum(&Path::new(&cfg.migrations_dir).join(prev));
+                    set_current_migration(&ses, prev, &checksum);
+                } else {
+                    del_current_migration(&ses);
 
-            let mut path = Path::new("./migrations").to_path_buf();
-            path.push(&get_current_migration(&ses).ioexpect("No migration currently applied"));
+                    if let Some(target) = to {
+                        iored(&format!("Reached the beginning of the migration list without finding {}", target));
+                    }
 
-            path.push("down.cql");
-            ioprint("Applying down.cql");
-            apply_migration(&ses, fs::read_to_string(&path).ioexpect("Error reading down.cql"));
-            ioprint("Applying up.cql");
-            apply_migration(&ses, fs::read_to_string(path.with_file_name("up.cql")).ioexpect("Error reading up.cql"));
+                    break;
+                }
 
-            iook("Redone successfully");
-        },
-        ("rollback", _) => {
-            let cfg = get_cfg();
-            let ses = init_single_connection(&cfg);
-            let cur = get_current_migration(&ses).ioexpect("No migration currently applied");
-
-            let mut path = Path::new("./migrations").to_path_buf();
-            path.push(&cur);
-
-            path.push("down.cql");
-            apply_migration(&ses, fs::read_to_string(path).ioexpect("Error reading down.cql"));
-
-            for (i, x) in cfg.migrations.iter().enumerate() {
-                if x.as_str() == cur {
-                    if i > 0 {
-                        set_current_migration(&ses, cfg.migrations[i-1].as_str());
-                    } else {
-                        del_current_migration(&ses);
-                    }
+                if to.is_none() {
+                    break;
                 }
             }
 
             iook("Rolled back")
         },
-        ("apply", _) => {
-            let cfg = get_cfg();
-            let ses = init_single_connection(&cfg);
-            
-            let name = match get_current_migration(&ses) {
-                None => cfg.migrations.first(),
-                Some(cur) => {
-                    let mut applied = false;
-                    let mut next = None;
-                    
-                    for x in &cfg.migrations {
-                        if applied {
-                            next = Some(x);
-                            
-                            break;
-                        } else if x.as_str() == cur {
-                            applied = true;
-                        }
-                    }
-
-                    next
+        "apply" => {
+            let start_idx = match get_current_migration_record(&ses) {
+                None => 0,
+                Some((cur, checksum)) => {
+                    warn_on_drift(&cur, &checksum, &cfg.migrations_dir);
+                    migration_index(&cfg.migrations, &cur) + 1
                 }
-            }.ioexpect("No migration to apply!");
+            };
 
-            let mut path = Path::new("./migrations").to_path_buf();
-            path.push(name);
-            path.push("up.cql");
+            let all = sub_matches.map(|m| m.is_present("all")).unwrap_or(false);
+            let to = sub_matches.and_then(|m| m.value_of("TO"));
 
-            apply_migration(&ses, fs::read_to_string(path).ioexpect("Error reading up.cql"));
-            set_current_migration(&ses, name);
+            let end_idx = match to {
+                Some(target) => migration_index(&cfg.migrations, target) + 1,
+                None if all => cfg.migrations.len(),
+                None => (start_idx + 1).min(cfg.migrations.len())
+            };
 
-            iook(&format!("Applied {}", name));
-        },
-        ("delete", _) => {
-            let cfg = get_cfg();
-            let ses = init_single_connection(&cfg);
+            if start_idx >= cfg.migrations.len() {
+                iored("No migration to apply!");
+                std::process::exit(-1);
+            }
+
+            if start_idx >= end_idx {
+                ioprint("Nothing to do; already at or past the requested target.");
+                return;
+            }
+
+            for name in &cfg.migrations[start_idx..end_idx] {
+                let path = Path::new(&cfg.migrations_dir).join(name);
 
-            let mut path = Path::new("./migrations").to_path_buf();
+                match apply_with_rollback(&ses, &path) {
+                    Ok(_) => {
+                        let checksum = migration_checksum(&path);
+                        set_current_migration(&ses, name, &checksum);
+
+                        iook(&format!("Applied {}", name));
+                    },
+                    Err(ApplyOutcome::RevertedCleanly) => {
+                        iook(&format!("{} failed partway through but was reverted cleanly; the keyspace is unchanged. Fix the migration and re-run apply.", name));
+                        std::process::exit(-1);
+                    },
+                    Err(ApplyOutcome::RevertFailed(e)) => {
+                        iored(&format!("{} failed partway through AND the automatic revert failed: {}. The keyspace is in an indeterminate state and needs manual cleanup.", name, e));
+                        std::process::exit(-1);
+                    }
+                }
+            }
+        },
+        "delete" => {
+            let mut path = Path::new(&cfg.migrations_dir).to_path_buf();
             path.push("migration");
 
             let mut vagabond: String = format!("\n{}\n", &cfg.vagabond);
@@ -342,7 +871,7 @@ fn main() {
                     fs::remove_dir_all(path.with_file_name(&x)).ioexpect("Error deleting directory");
                     vagabond = vagabond.replace(&format!("\n{}\n", x), "\n");
                 };
-                
+
                 match get_current_migration(&ses) {
                     Some(cur) => {
                         let mut applied = true;
@@ -365,20 +894,23 @@ fn main() {
                 }
             }
 
-            fs::write("./migrations/vagabond", &vagabond[1..vagabond.len()-1]).ioexpect("Error writing to vagabond");
+            if cfg.uses_vagabond_file {
+                fs::write(format!("{}/vagabond", cfg.migrations_dir), &vagabond[1..vagabond.len()-1]).ioexpect("Error writing to vagabond");
+            }
         },
         _ => {
-            let cfg = get_cfg();
-            let current = get_current_migration(&init_single_connection(&cfg));
+            let current = get_current_migration_record(&ses);
 
             match current {
-                Some(cur) => {
+                Some((cur, checksum)) => {
+                    warn_on_drift(&cur, &checksum, &cfg.migrations_dir);
+
                     let mut applied = true;
-                    
+
                     for x in cfg.migrations {
                         if applied {
                             iook(&format!("✅ {}", x));
-                            
+
                             if x == cur {
                                 applied = false;
                             }
@@ -396,3 +928,103 @@ fn main() {
         }
     }
 }
+
+fn main() {
+    kankyo::load().unwrap();
+
+    let matches = clap_app!(vagabond =>
+        (version: crate_version!())
+        (author: crate_authors!())
+        (about: crate_description!())
+        (about: "A very simple cassandra migration tool for rust.")
+        (@arg config: -c --config +takes_value "Path to vagabond.toml (defaults to ./vagabond.toml)")
+
+        (@subcommand init =>
+            (about: "Initialize the migrations directory")
+        )
+
+        (@subcommand new =>
+            (about: "Add new migration")
+            (@arg NAME: * "Name of migration")
+        )
+
+        (@subcommand redo =>
+            (about: "Undoes and applies the last migration")
+        )
+
+        (@subcommand rollback =>
+            (about: "Undoes the last migration, or every migration down to --to")
+            (@arg TO: --to +takes_value "Roll back until this migration is the current one")
+        )
+
+        (@subcommand apply =>
+            (about: "Applies the next migration, or every pending one with --all/--to")
+            (@arg all: --all "Apply every pending migration")
+            (@arg TO: --to +takes_value "Apply migrations up to and including this one")
+        )
+
+        (@subcommand delete =>
+            (about: "Deletes all unapplied migrations")
+        )
+    ).get_matches();
+
+    let config_path = matches.value_of("config");
+
+    match matches.subcommand() {
+        ("init", _) => {
+            let file = load_file_config(Path::new(config_path.unwrap_or(DEFAULT_CONFIG_PATH)));
+            let migrations_dir = resolve_migrations_dir(&file);
+
+            // No `vagabond` ordering file: fresh repos are pure-timestamp,
+            // so there's nothing for two branches adding migrations to
+            // conflict over.
+            fs::create_dir(&migrations_dir).ioexpect("Cannot create directory");
+
+            let example_path = config_path.unwrap_or(DEFAULT_CONFIG_PATH);
+            fs::write(example_path, EXAMPLE_CONFIG).ioexpect("Error writing vagabond.toml");
+
+            iook(&format!("{} initialized", migrations_dir));
+        },
+        ("new", Some(args)) => {
+            let name: &str = args.value_of("NAME").unwrap();
+            let cfg = get_cfg(config_path);
+
+            if cfg.migrations.iter().any(|m| migration_label(m) == name) {
+                panic!("Migration name is already used!");
+            }
+
+            // Timestamp-prefixed so ordering comes from sorting directory
+            // names instead of a hand-edited list that two branches can
+            // conflict on.
+            let versioned = format!("{}_{}", Utc::now().format("%Y%m%d%H%M%S"), name);
+
+            let mut path = Path::new(&cfg.migrations_dir).to_path_buf();
+            path.push(&versioned);
+
+            fs::create_dir(&path).ioexpect("Cannot create directory");
+
+            path.push("./up.cql");
+            fs::write(&path, "").ioexpect("Error creating up.cql");
+
+            fs::write(path.with_file_name("down.cql"), "").ioexpect("Error creating down.cql");
+
+            if cfg.uses_vagabond_file {
+                fs::write(format!("{}/vagabond", cfg.migrations_dir), format!("{}\n{}", cfg.vagabond, versioned)).ioexpect("Error writing to vagabond");
+            }
+
+            iook(&format!("Migration created: {}", versioned));
+        },
+        (sub, sub_matches) => {
+            let cfg = get_cfg(config_path);
+
+            match (cfg.tls, cfg.lb) {
+                (false, LbStrategy::SingleNode) => run_with_session(cfg, |c| connect_tcp(c, SingleNode::new()), sub, sub_matches),
+                (false, LbStrategy::RoundRobin) => run_with_session(cfg, |c| connect_tcp(c, RoundRobin::new()), sub, sub_matches),
+                (false, LbStrategy::Random) => run_with_session(cfg, |c| connect_tcp(c, Random::new()), sub, sub_matches),
+                (true, LbStrategy::SingleNode) => run_with_session(cfg, |c| connect_ssl(c, SingleNode::new()), sub, sub_matches),
+                (true, LbStrategy::RoundRobin) => run_with_session(cfg, |c| connect_ssl(c, RoundRobin::new()), sub, sub_matches),
+                (true, LbStrategy::Random) => run_with_session(cfg, |c| connect_ssl(c, Random::new()), sub, sub_matches)
+            }
+        }
+    }
+}